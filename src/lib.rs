@@ -21,6 +21,16 @@ pub enum UrlPath{
         /// the last element of the url when split with `/`
         last: Option<String>,
         is_absolute: bool,
+        /// number of leading `..` segments that escape the root, kept so a
+        /// relative path still points at the same file after normalization
+        supers: usize,
+        /// the `?`-introduced query string, without its leading `?`
+        query: Option<String>,
+        /// the `#`-introduced fragment, without its leading `#`
+        fragment: Option<String>,
+        /// whether the raw input ended with `/`, kept so directory urls
+        /// stay directories (`docs/` resolves links differently from `docs`)
+        is_dir: bool,
     },
     External(String),
 }
@@ -28,10 +38,26 @@ pub enum UrlPath{
 impl UrlPath{
 
     pub fn new(path: &str) -> Self {
-        let (parent, last) = Self::canonicalize(path);
-        let is_absolute = path.starts_with("/");
-        let is_external = path.starts_with("http:")
-            || path.starts_with("https:");
+        if path.starts_with("file:"){
+            if let Some(url_path) = Self::from_file_url(path){
+                return url_path;
+            }
+        }
+        // peel off the fragment, then the query, before the path itself is
+        // canonicalized, so `?...` and `#...` never leak into `last`
+        let (path_and_query, fragment) = match path.find('#'){
+            Some(idx) => (&path[..idx], Some(path[idx + 1..].to_string())),
+            None => (path, None),
+        };
+        let (path_only, query) = match path_and_query.find('?'){
+            Some(idx) => (&path_and_query[..idx], Some(path_and_query[idx + 1..].to_string())),
+            None => (path_and_query, None),
+        };
+        let (parent, last, supers) = Self::canonicalize(path_only);
+        let is_absolute = path_only.starts_with("/");
+        let is_dir = path_only.ends_with("/");
+        let is_external = path_only.starts_with("http:")
+            || path_only.starts_with("https:");
         if is_external{
             UrlPath::External(path.to_string())
         }else{
@@ -39,10 +65,145 @@ impl UrlPath{
                 parent,
                 last,
                 is_absolute,
+                supers,
+                query,
+                fragment,
+                is_dir,
             }
         }
     }
 
+    /// resolve `self` as a relative reference against the `base` path,
+    /// following RFC 3986 relative-reference rules. If `self` is already
+    /// absolute or external it is returned in its normalized form without
+    /// reference to `base`; otherwise `self` is
+    /// concatenated onto the parent directory of `base` and the combined
+    /// path is run through the dot-segment canonicalization.
+    pub fn resolve(&self, base: &UrlPath) -> String {
+        if self.is_absolute() || self.is_external(){
+            return self.normalize();
+        }
+        match base {
+            UrlPath::External(ref url) => {
+                let (prefix, base_path) = Self::split_authority(url);
+                let base_dir = Self::parent_dir(&base_path);
+                let combined = format!("{}/{}", base_dir, self.normalize());
+                format!("{}{}", prefix, UrlPath::new(&combined).normalize())
+            }
+            UrlPath::Path{..} => {
+                let base_dir = Self::parent_dir(&base.normalize());
+                let combined = if base_dir.is_empty() && !base.is_absolute(){
+                    self.normalize()
+                }else{
+                    format!("{}/{}", base_dir, self.normalize())
+                };
+                UrlPath::new(&combined).normalize()
+            }
+        }
+    }
+
+    /// the parent directory of a path, ie. everything up to but not
+    /// including the last `/`-delimited segment
+    fn parent_dir(path: &str) -> String {
+        match path.rfind('/'){
+            Some(idx) => path[..idx].to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// split an external url into its scheme+authority prefix and the
+    /// path component, so the prefix can be preserved while the path is
+    /// resolved, eg. `https://host/a/b.html` -> (`https://host`, `/a/b.html`)
+    fn split_authority(url: &str) -> (String, String) {
+        if let Some(scheme_end) = url.find("://"){
+            let after = scheme_end + 3;
+            if let Some(slash) = url[after..].find('/'){
+                let idx = after + slash;
+                (url[..idx].to_string(), url[idx..].to_string())
+            }else{
+                (url.to_string(), "/".to_string())
+            }
+        }else{
+            (String::new(), url.to_string())
+        }
+    }
+
+    /// emit this path as a `file:` URL, percent-encoding the normalized
+    /// path and anchoring it at the root, eg. `file:///home/user/README.md`.
+    /// Returns `None` for `External` values.
+    pub fn to_file_url(&self) -> Option<String> {
+        match self{
+            UrlPath::External(_) => None,
+            UrlPath::Path{..} => {
+                let normalized = self.normalize();
+                let path = if normalized.starts_with('/'){
+                    normalized
+                }else{
+                    format!("/{}", normalized)
+                };
+                Some(format!("file://{}", Self::percent_encode(&path)))
+            }
+        }
+    }
+
+    /// parse a `file:` URL back into a `UrlPath`, dropping any authority
+    /// and percent-decoding the path. Returns `None` when `s` is not a
+    /// `file:` URL.
+    pub fn from_file_url(s: &str) -> Option<UrlPath> {
+        let rest = s.strip_prefix("file:")?;
+        let path = if let Some(after) = rest.strip_prefix("//"){
+            match after.find('/'){
+                Some(idx) => &after[idx..],
+                None => "/",
+            }
+        }else{
+            rest
+        };
+        Some(UrlPath::new(&Self::percent_decode(path)))
+    }
+
+    /// percent-decode a path, leaving the `/` separators in place
+    fn percent_decode(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out:Vec<u8> = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len(){
+            if bytes[i] == b'%' && i + 2 < bytes.len(){
+                if let (Some(hi), Some(lo)) = (Self::hex_val(bytes[i + 1]), Self::hex_val(bytes[i + 2])){
+                    out.push(hi * 16 + lo);
+                    i += 3;
+                    continue;
+                }
+            }
+            out.push(bytes[i]);
+            i += 1;
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    /// percent-encode a path, preserving the unreserved characters and the
+    /// `/` separators
+    fn percent_encode(s: &str) -> String {
+        let mut out = String::new();
+        for &b in s.as_bytes(){
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~' | b'/'){
+                out.push(b as char);
+            }else{
+                out.push_str(&format!("%{:02X}", b));
+            }
+        }
+        out
+    }
+
+    fn hex_val(b: u8) -> Option<u8> {
+        match b{
+            b'0'..=b'9' => Some(b - b'0'),
+            b'a'..=b'f' => Some(b - b'a' + 10),
+            b'A'..=b'F' => Some(b - b'A' + 10),
+            _ => None,
+        }
+    }
+
     pub fn is_absolute(&self) -> bool {
         match self{
             UrlPath::Path{ref is_absolute,..} => *is_absolute,
@@ -59,26 +220,37 @@ impl UrlPath{
 
     /// use own implementation of canonicalize since fs::canonicalize
     /// requires the file to be there
-    fn canonicalize(path: &str) -> (Option<String>, Option<String>) {
-        let segments:Vec<&str> = path.split("/").collect();
-        let mut path:Vec<String> = vec![];
-        let segments2:Vec<&str> = segments.into_iter()
-                .filter(|s|!(s.is_empty() || *s == ".")).collect();
-        let _filtered:Vec<&str> = segments2.into_iter()
-            .inspect(|s| 
-                 if *s == ".."{
-                    path.pop();
-                 }else{
-                    path.push(s.to_string())
-                 }).collect();
-        let filename = path.pop();
-        let parent = path.join("/");
+    fn canonicalize(path: &str) -> (Option<String>, Option<String>, usize) {
+        let is_absolute = path.starts_with("/");
+        let mut stack:Vec<String> = vec![];
+        let mut supers = 0;
+        for seg in path.split("/").filter(|s|!(s.is_empty() || *s == ".")){
+            // a run of `n` dots (n >= 2) climbs `n - 1` parent directories,
+            // so `..` is one traversal, `...` is two, `....` is three, ...
+            if seg.chars().all(|c| c == '.'){
+                for _ in 0..seg.len() - 1{
+                    if stack.is_empty(){
+                        // escaping the working directory; an absolute path
+                        // can not climb above `/` so the operator is dropped
+                        if !is_absolute{
+                            supers += 1;
+                        }
+                    }else{
+                        stack.pop();
+                    }
+                }
+            }else{
+                stack.push(seg.to_string());
+            }
+        }
+        let filename = stack.pop();
+        let parent = stack.join("/");
         let parent = if parent.is_empty(){
             None
         }else{
             Some(parent)
         };
-        (parent, filename)
+        (parent, filename, supers)
     }
 
     pub fn last(&self) -> Option<String> {
@@ -95,33 +267,161 @@ impl UrlPath{
         }
     }
 
+    /// the number of leading `..` segments that escape the root, ie. how
+    /// many parent directories this relative path climbs before its first
+    /// named component
+    pub fn supers(&self) -> usize {
+        match self{
+            UrlPath::Path{supers,..} => *supers,
+            UrlPath::External(_) => 0,
+        }
+    }
+
+    pub fn query(&self) -> Option<String> {
+        match self{
+            UrlPath::Path{query,..} => query.clone(),
+            UrlPath::External(_) => None,
+        }
+    }
+
+    pub fn fragment(&self) -> Option<String> {
+        match self{
+            UrlPath::Path{fragment,..} => fragment.clone(),
+            UrlPath::External(_) => None,
+        }
+    }
+
+    /// whether this path denotes a directory, ie. the raw input ended
+    /// with a trailing `/`
+    pub fn is_dir(&self) -> bool {
+        match self{
+            UrlPath::Path{is_dir,..} => *is_dir,
+            UrlPath::External(_) => false,
+        }
+    }
+
+
+    /// append a single plain segment to the path. The segment may not
+    /// contain a `/`; `None` is returned when it does, or when called on
+    /// an `External` value.
+    pub fn push_segment(&mut self, seg: &str) -> Option<()> {
+        if self.is_external() || seg.contains('/'){
+            return None;
+        }
+        let base = self.normalize();
+        let combined = if base.is_empty(){
+            seg.to_string()
+        }else{
+            format!("{}/{}", base, seg)
+        };
+        *self = UrlPath::new(&combined);
+        Some(())
+    }
+
+    /// append another relative path and re-canonicalize the result.
+    /// A no-op returning `None` on an `External` value.
+    pub fn push(&mut self, relative: &UrlPath) -> Option<()> {
+        if self.is_external(){
+            return None;
+        }
+        let base = self.normalize();
+        let rel = relative.normalize();
+        let combined = if base.is_empty(){
+            rel
+        }else{
+            format!("{}/{}", base, rel)
+        };
+        *self = UrlPath::new(&combined);
+        Some(())
+    }
+
+    /// remove and return the last segment, promoting the previous parent
+    /// component into `last`. Returns `None` on an `External` value or when
+    /// there is nothing left to pop.
+    pub fn pop(&mut self) -> Option<String> {
+        if self.is_external(){
+            return None;
+        }
+        let removed = self.last();
+        let mut base = String::new();
+        if self.is_absolute(){
+            base.push('/');
+        }
+        base.push_str(&"../".repeat(self.supers()));
+        if let Some(parent) = self.parent(){
+            base.push_str(&parent);
+        }
+        *self = UrlPath::new(&base);
+        removed
+    }
+
+    /// the dotted suffix of the last segment, if any (eg. `png` for
+    /// `logo.png`). Leading-dot names like `.gitignore` have no extension.
+    pub fn extension(&self) -> Option<String> {
+        self.last().and_then(|last|{
+            match last.rfind('.'){
+                Some(idx) if idx > 0 => Some(last[idx + 1..].to_string()),
+                _ => None,
+            }
+        })
+    }
+
+    /// replace the dotted suffix of the last segment with `ext`; an empty
+    /// `ext` strips the extension. A no-op returning `None` on an
+    /// `External` value or when there is no last segment.
+    pub fn set_extension(&mut self, ext: &str) -> Option<()> {
+        match self{
+            UrlPath::External(_) => None,
+            UrlPath::Path{last,..} => {
+                let name = last.as_mut()?;
+                let stem = match name.rfind('.'){
+                    Some(idx) if idx > 0 => name[..idx].to_string(),
+                    _ => name.clone(),
+                };
+                *name = if ext.is_empty(){
+                    stem
+                }else{
+                    format!("{}.{}", stem, ext)
+                };
+                Some(())
+            }
+        }
+    }
 
     pub fn normalize(&self) -> String {
         match self{
-            UrlPath::Path{parent, last, is_absolute} => {
+            UrlPath::Path{parent, last, is_absolute, supers, query, fragment, is_dir} => {
                 let full_path = if let Some(ref parent) = parent {
                     if let Some(ref file) = last{
                         format!("{}/{}", parent, file)
                     }else{
-                        format!("{}", parent)
+                        parent.to_string()
                     }
                 }
                 else if let Some(ref file) = last{
-                    if let Some(ref parent) = parent{
-                        format!("{}/{}", parent, file)
-                    }else{
-                        format!("{}", file)
-                    }
+                    file.to_string()
                 }
                 else{
                     "".to_string()
                 };
 
-                if *is_absolute{
+                let mut result = if *is_absolute{
                     format!("/{}", full_path)
                 }else{
-                    full_path
+                    format!("{}{}", "../".repeat(*supers), full_path)
+                };
+                if *is_dir && !result.is_empty() && !result.ends_with('/'){
+                    result.push('/');
                 }
+                if let Some(ref query) = query{
+                    result.push('?');
+                    result.push_str(query);
+                }
+                if let Some(ref fragment) = fragment{
+                    result.push('#');
+                    result.push_str(fragment);
+                }
+                result
             }
             UrlPath::External(ref s) => s.to_string(),
         }
@@ -217,12 +517,144 @@ mod tests {
         assert_eq!(None, path.parent());
     }
 
+    #[test]
+    fn supers_round_trip() {
+        let url = "../../README.md";
+        let path = UrlPath::new(url);
+        assert_eq!(2, path.supers());
+        assert_eq!("../../README.md", path.normalize());
+    }
+
+    #[test]
+    fn supers_escaping() {
+        let url = "a/../../b";
+        let path = UrlPath::new(url);
+        assert_eq!(1, path.supers());
+        assert_eq!("../b", path.normalize());
+    }
+
+    #[test]
+    fn supers_clamped_when_absolute() {
+        let url = "/a/../../b";
+        let path = UrlPath::new(url);
+        assert_eq!(0, path.supers());
+        assert_eq!("/b", path.normalize());
+    }
+
+    #[test]
+    fn n_dots_escaping() {
+        let path = UrlPath::new("a/.../b");
+        assert_eq!("../b", path.normalize());
+    }
+
+    #[test]
+    fn n_dots_within_bounds() {
+        let path = UrlPath::new("x/y/.../z");
+        assert_eq!("z", path.normalize());
+    }
+
+    #[test]
+    fn trailing_slash_preserved() {
+        let path = UrlPath::new("docs/");
+        assert!(path.is_dir());
+        assert_eq!("docs/", path.normalize());
+    }
+
+    #[test]
+    fn query_and_fragment() {
+        let url = "a/../b.html?x=1#f";
+        let path = UrlPath::new(url);
+        assert_eq!("b.html?x=1#f", path.normalize());
+        assert_eq!(Some("x=1".to_string()), path.query());
+        assert_eq!(Some("f".to_string()), path.fragment());
+    }
+
+    #[test]
+    fn file_url_round_trip() {
+        let url = "file:///home/user/README.md";
+        let path = UrlPath::new(url);
+        assert!(!path.is_external());
+        assert_eq!("/home/user/README.md", path.normalize());
+        assert_eq!(Some(url.to_string()), path.to_file_url());
+    }
+
+    #[test]
+    fn file_url_decodes_space() {
+        let path = UrlPath::new("file:///home/user/my%20file.md");
+        assert_eq!("/home/user/my file.md", path.normalize());
+        assert_eq!(
+            Some("file:///home/user/my%20file.md".to_string()),
+            path.to_file_url()
+        );
+    }
+
+    #[test]
+    fn push_segment_and_pop() {
+        let mut path = UrlPath::new("docs/guide");
+        assert_eq!(Some(()), path.push_segment("page.html"));
+        assert_eq!("docs/guide/page.html", path.normalize());
+        assert_eq!(Some("page.html".to_string()), path.pop());
+        assert_eq!("docs/guide", path.normalize());
+    }
+
+    #[test]
+    fn push_segment_rejects_slash() {
+        let mut path = UrlPath::new("docs");
+        assert_eq!(None, path.push_segment("a/b"));
+        assert_eq!("docs", path.normalize());
+    }
+
+    #[test]
+    fn push_relative_path() {
+        let mut path = UrlPath::new("docs/guide/page.html");
+        path.pop();
+        assert_eq!(Some(()), path.push(&UrlPath::new("../img/logo.png")));
+        assert_eq!("docs/img/logo.png", path.normalize());
+    }
+
+    #[test]
+    fn set_and_get_extension() {
+        let mut path = UrlPath::new("docs/logo.png");
+        assert_eq!(Some("png".to_string()), path.extension());
+        assert_eq!(Some(()), path.set_extension("svg"));
+        assert_eq!("docs/logo.svg", path.normalize());
+    }
+
+    #[test]
+    fn mutators_noop_on_external() {
+        let mut path = UrlPath::new("https://host/a.png");
+        assert_eq!(None, path.push_segment("b"));
+        assert_eq!(None, path.pop());
+        assert_eq!(None, path.set_extension("svg"));
+    }
+
+    #[test]
+    fn resolve_absolute_reference() {
+        let base = UrlPath::new("docs/guide/page.html");
+        let link = UrlPath::new("/img/logo.png");
+        assert_eq!("/img/logo.png", link.resolve(&base));
+    }
+
+    #[test]
+    fn resolve_parent_escaping() {
+        let base = UrlPath::new("docs/guide/page.html");
+        let link = UrlPath::new("../img/logo.png");
+        assert_eq!("docs/img/logo.png", link.resolve(&base));
+    }
+
+    #[test]
+    fn resolve_external_base() {
+        let base = UrlPath::new("https://host/a/b.html");
+        let link = UrlPath::new("../c.png");
+        assert_eq!("https://host/c.png", link.resolve(&base));
+    }
+
     #[test]
     fn normalize_no_more_back() {
         let url = "../../README.md";
         let path = UrlPath::new(url);
         let result = path.normalize();
-        let expected = "README.md";
+        let expected = "../../README.md";
         assert_eq!(expected, result);
     }
 }